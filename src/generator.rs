@@ -1,6 +1,13 @@
 use rand_distr::{Distribution, Exp};
 use std::time::Duration;
 
+/// A start-time schedule: an iterator of offsets (from the start of the measurement
+/// window) at which requests should be issued, plus a hint of how many offsets it will
+/// produce so callers can preallocate.
+pub trait ArrivalGen: Iterator<Item = Duration> {
+    fn expected_len(&self) -> usize;
+}
+
 pub struct ConstGen {
     now: Duration,
     duration: Duration,
@@ -17,10 +24,6 @@ impl ConstGen {
             expected: (duration.as_secs() * rate) as usize,
         }
     }
-
-    pub fn expected_len(&self) -> usize {
-        self.expected
-    }
 }
 
 impl Iterator for ConstGen {
@@ -36,8 +39,46 @@ impl Iterator for ConstGen {
     }
 }
 
-#[allow(unused)]
-pub fn new_exp(duration: Duration, rate: u64) -> Vec<Duration> {
+impl ArrivalGen for ConstGen {
+    fn expected_len(&self) -> usize {
+        self.expected
+    }
+}
+
+/// Open-loop Poisson arrival process: inter-arrival times are drawn from an exponential
+/// distribution with the given `rate`, so requests arrive in bursts the way real traffic
+/// does instead of perfectly spaced out.
+pub struct PoissonGen {
+    offsets: std::vec::IntoIter<Duration>,
+    expected: usize,
+}
+
+impl PoissonGen {
+    pub fn new(duration: Duration, rate: u64) -> Self {
+        let offsets = new_exp(duration, rate);
+        let expected = offsets.len();
+        Self {
+            offsets: offsets.into_iter(),
+            expected,
+        }
+    }
+}
+
+impl Iterator for PoissonGen {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.offsets.next()
+    }
+}
+
+impl ArrivalGen for PoissonGen {
+    fn expected_len(&self) -> usize {
+        self.expected
+    }
+}
+
+fn new_exp(duration: Duration, rate: u64) -> Vec<Duration> {
     let dist = Exp::new(rate as f64).unwrap();
     dist.sample_iter(rand::thread_rng())
         .map(Duration::from_secs_f64)
@@ -1,335 +1,777 @@
-mod generator;
-mod workload;
-
-#[macro_use]
-extern crate log;
-
-use crate::generator::ConstGen;
-use clap::{Parser, ValueEnum};
-use reqwest::{Client, StatusCode};
-use std::{
-    fmt,
-    fs::File,
-    io::Write,
-    path::PathBuf,
-    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
-};
-use tokio::{runtime::Builder, sync::mpsc};
-
-const RATE_INC_PER_SEC: u64 = 1000;
-const REQ_ISSUE_SLACK_MS: u64 = 100;
-
-type Result<T> = core::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
-
-#[derive(Clone, Copy, ValueEnum)]
-enum RequestType {
-    Matmul,
-    Compute,
-    Io,
-}
-
-impl fmt::Display for RequestType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_possible_value().unwrap().get_name())
-    }
-}
-
-#[derive(Parser)]
-struct Args {
-    /// IP address to issue requests to
-    #[arg(long, default_value_t = String::from("localhost"))]
-    ip: String,
-
-    /// Number of seconds to run measurement for
-    #[arg(short, long, default_value_t = 1)]
-    duration: u64,
-
-    /// Number of requests per second to issue, 0 means as fast as possible
-    #[arg(short, long, default_value_t = 1)]
-    rate: u64,
-
-    /// The amount of users (maximum number of concurrent requests)
-    #[arg(long, default_value_t = 10000)]
-    num_users: usize,
-
-    /// Request timeout in milliseconds
-    #[arg(long, default_value_t = 10000)]
-    timeout: u64,
-
-    // Disable warmup phase before measurement
-    #[arg(long)]
-    no_warmup: bool,
-
-    /// Path to output results
-    #[arg(long)]
-    output_file: Option<PathBuf>,
-
-    /// What kind of requests to send
-    #[arg(long, default_value_t = RequestType::Matmul)]
-    request_type: RequestType,
-
-    /// Size (N) of the NxN matrix to multiply / number of iterations to compute
-    #[arg(long, default_value_t = 128)]
-    input_size: u64,
-
-    /// The IP of the HTTP storage server (used only for the composition experiment)
-    #[arg(long, default_value_t = String::from("localhost"))]
-    storage_ip: String,
-
-    /// The average percentage of hot requests to issue
-    #[arg(long, default_value_t = 1.0)]
-    hot_percent: f64,
-}
-
-struct HotGenerator {
-    hot_percent: f64,
-    request_counter: f64,
-}
-
-impl HotGenerator {
-    fn new(hot_percent: f64) -> Self {
-        assert!(hot_percent >= 0.0);
-        assert!(hot_percent <= 1.0);
-        Self {
-            hot_percent,
-            request_counter: rand::random(),
-        }
-    }
-
-    fn next(&mut self) -> bool {
-        self.request_counter += self.hot_percent;
-        if self.request_counter >= 1.0 {
-            self.request_counter -= 1.0;
-            true
-        } else {
-            false
-        }
-    }
-}
-
-#[derive(Debug)]
-struct Record {
-    start: SystemTime,
-    end: SystemTime,
-    url: String,
-    timeout: bool,
-    error: bool,
-    status: Option<StatusCode>,
-}
-
-impl Record {
-    fn start_time(&self) -> Duration {
-        self.start.duration_since(UNIX_EPOCH).unwrap()
-    }
-
-    fn duration(&self) -> Duration {
-        self.end.duration_since(self.start).unwrap()
-    }
-}
-
-struct BenchLog {
-    records: Vec<Record>,
-    timeouts: usize,
-    errors: usize,
-}
-
-impl BenchLog {
-    fn new(num_records: usize) -> Self {
-        Self {
-            records: Vec::with_capacity(num_records),
-            timeouts: 0,
-            errors: 0,
-        }
-    }
-
-    fn add_record(&mut self, record: Record) {
-        if record.timeout {
-            self.timeouts += 1;
-        }
-        if record.error {
-            self.errors += 1;
-        }
-        self.records.push(record);
-    }
-
-    fn total(&self) -> usize {
-        self.records.len()
-    }
-
-    fn errors(&self) -> usize {
-        self.timeouts + self.errors
-    }
-
-    fn latencies(&self, percentages: &[f64]) -> Vec<Duration> {
-        let mut latency: Vec<_> = self.records.iter().map(|t| t.duration()).collect();
-        latency.sort();
-        percentages
-            .iter()
-            .map(|p| {
-                latency
-                    .get(((latency.len() as f64 * p - 1.0) / 100.0) as usize)
-                    .cloned()
-                    .unwrap_or_default()
-            })
-            .collect()
-    }
-}
-
-fn main() -> Result<()> {
-    env_logger::init();
-    let args = Args::parse();
-    let rt = Builder::new_multi_thread().enable_all().build()?;
-    rt.block_on(tokio_main(args))
-}
-
-// #[tokio::main]
-async fn tokio_main(args: Args) -> Result<()> {
-    let mut hot_gen = HotGenerator::new(args.hot_percent);
-    let expected_checksum = args.request_type.checksum(args.input_size);
-
-    let client = Client::builder()
-        .timeout(Duration::from_millis(args.timeout))
-        .build()
-        .unwrap();
-
-    let mut rate_per_sec = if args.no_warmup {
-        vec![]
-    } else {
-        (RATE_INC_PER_SEC..args.rate)
-            .step_by(RATE_INC_PER_SEC as usize)
-            .collect()
-    };
-    let num_warmup = rate_per_sec.iter().sum::<u64>() as usize;
-    rate_per_sec.extend(std::iter::repeat(args.rate).take(args.duration as usize));
-    let starts = ConstGen::new(rate_per_sec);
-    let mut bench_log = BenchLog::new(starts.expected_len() + 1);
-
-    let (step_tx, mut step_rx) = mpsc::channel(100);
-    let (user_tx, mut user_rx) = mpsc::channel(args.num_users);
-    let (record_tx, mut record_rx) = mpsc::channel(100);
-
-    let task_timer = tokio::task::spawn_blocking(move || {
-        let base = Instant::now();
-        if args.rate == 0 {
-            while base.elapsed().as_secs() < args.duration {
-                step_tx.blocking_send(()).unwrap();
-            }
-        } else {
-            for start in starts {
-                let next = base + start;
-                if next.elapsed() > Duration::from_millis(REQ_ISSUE_SLACK_MS) {
-                    warn!("Could not keep up with needed rate, canceling experiment");
-                    let msg: Box<dyn std::error::Error + Send + Sync> = "Could not keep up".into();
-                    return Err(msg);
-                }
-                // higher precision than tokio::time::sleep
-                std::thread::sleep(next - Instant::now());
-                step_tx.blocking_send(()).unwrap();
-            }
-        }
-        info!("Started all requests in {:?}", base.elapsed());
-        Ok(())
-    });
-
-    for user_id in 0..args.num_users {
-        user_tx.send(user_id).await.unwrap();
-    }
-
-    tokio::spawn(async move {
-        while let Some(_) = step_rx.recv().await {
-            let is_hot = hot_gen.next();
-            let url = args.request_type.url(&args.ip, is_hot);
-            let body = args.request_type.body(args.input_size, &args.storage_ip);
-            let request = client.post(&url).body(body);
-
-            let user_id = user_rx.recv().await.unwrap();
-            let user_tx = user_tx.clone();
-            let record_tx = record_tx.clone();
-            tokio::spawn(async move {
-                let start = SystemTime::now();
-                let result = request.send().await;
-                let end = SystemTime::now();
-
-                let mut record = Record {
-                    start,
-                    end,
-                    url,
-                    timeout: false,
-                    error: false,
-                    status: None,
-                };
-                match result.and_then(|r| r.error_for_status()) {
-                    Ok(r) => {
-                        record.status = Some(r.status());
-                        let body = r.bytes().await.unwrap();
-                        assert_eq!(body.len(), 8);
-                        let mut buf = [0u8; 8];
-                        buf.copy_from_slice(&body[..8]);
-                        let checksum = u64::from_be_bytes(buf);
-                        assert_eq!(checksum, expected_checksum);
-                    }
-                    Err(e) => {
-                        if e.is_timeout() {
-                            record.timeout = true;
-                            debug!("Request timed out");
-                        } else {
-                            record.error = true;
-                            record.status = e.status();
-                            warn!("Request error: {}", e);
-                        }
-                    }
-                }
-                // user_rx could be dropped first (when timer_tx is closed), so we don't check the result here.
-                let _ = user_tx.send(user_id).await;
-                record_tx.send(record).await.unwrap();
-            });
-        }
-    });
-
-    let mut num_received = 0;
-    while let Some(record) = record_rx.recv().await {
-        num_received += 1;
-        if num_received > num_warmup {
-            bench_log.add_record(record);
-        }
-    }
-
-    if let Some(results_path) = args.output_file {
-        let mut file = File::create(results_path)?;
-        writeln!(
-            file,
-            "instance,startTime,responseTime,connectionTimeout,functionTimeout,statusCode",
-        )?;
-        for record in &bench_log.records {
-            writeln!(
-                file,
-                "{},{},{},{},{},{}",
-                record.url,
-                record.start_time().as_micros(),
-                record.duration().as_micros(),
-                record.timeout,
-                record.error,
-                record.status.map_or(0, |s| s.as_u16()),
-            )?;
-        }
-    }
-
-    let num_total = bench_log.total();
-    let num_errors = bench_log.errors();
-    println!("Total: {}, Errors: {}", num_total, num_errors);
-    let percentages = [50.0, 90.0, 95.0, 99.0, 99.9, 100.0];
-    let latencies = bench_log.latencies(&percentages);
-    println!("Latency percentiles (in us):");
-    for (p, l) in percentages.into_iter().zip(latencies) {
-        println!("{:5}% -- {}\t", p, l.as_micros());
-    }
-    // required by the experiment script
-    task_timer.await??;
-    println!(
-        "error%=\"{}\" goodput=\"{}\"",
-        num_errors as f64 / num_total as f64,
-        (num_total - num_errors) as f64 / args.duration as f64,
-    );
-
-    Ok(())
-}
+mod generator;
+mod metrics;
+mod workload;
+
+#[macro_use]
+extern crate log;
+
+use crate::{
+    generator::{ArrivalGen, ConstGen, PoissonGen},
+    metrics::Metrics,
+};
+use clap::{Parser, ValueEnum};
+use futures_util::{SinkExt, StreamExt};
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+use std::{
+    fmt,
+    fs::File,
+    io::Write,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{net::TcpStream, runtime::Builder, sync::mpsc};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+const REQ_ISSUE_SLACK_MS: u64 = 100;
+const PERCENTAGES: [f64; 6] = [50.0, 90.0, 95.0, 99.0, 99.9, 100.0];
+
+pub(crate) type Result<T> = core::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Clone, Copy, ValueEnum, PartialEq, Eq)]
+enum RequestType {
+    Matmul,
+    Compute,
+    Io,
+    Websocket,
+}
+
+impl fmt::Display for RequestType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Arrival {
+    Constant,
+    Poisson,
+}
+
+impl fmt::Display for Arrival {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+#[derive(Parser)]
+struct Args {
+    /// IP address to issue requests to
+    #[arg(long, default_value_t = String::from("localhost"))]
+    ip: String,
+
+    /// Number of seconds to run each measurement window for
+    #[arg(short, long, default_value_t = 1)]
+    duration: u64,
+
+    /// Number of requests per second to start the sweep at, 0 means as fast as possible
+    #[arg(short, long, default_value_t = 1)]
+    rate: u64,
+
+    /// Increase the offered rate by this many requests/sec after each window
+    #[arg(long)]
+    rate_step: Option<u64>,
+
+    /// Stop increasing the offered rate once it reaches this many requests/sec
+    #[arg(long)]
+    rate_max: Option<u64>,
+
+    /// Number of measurement windows to run once rate_max is reached
+    #[arg(long, default_value_t = 1)]
+    max_iter: u64,
+
+    /// Open-loop arrival process used to schedule requests
+    #[arg(long, default_value_t = Arrival::Constant)]
+    arrival: Arrival,
+
+    /// The amount of users (maximum number of concurrent requests)
+    #[arg(long, default_value_t = 10000)]
+    num_users: usize,
+
+    /// Request timeout in milliseconds
+    #[arg(long, default_value_t = 10000)]
+    timeout: u64,
+
+    /// Path to output results
+    #[arg(long)]
+    output_file: Option<PathBuf>,
+
+    /// Repeat the entire sweep this many times and aggregate mean/median statistics
+    #[arg(long, default_value_t = 1)]
+    samples: u64,
+
+    /// Path to write the aggregated samples summary as JSON
+    #[arg(long)]
+    json_summary: Option<PathBuf>,
+
+    /// Serve live Prometheus metrics (text exposition format) on this address, e.g. 0.0.0.0:9090
+    #[arg(long)]
+    prometheus_listen: Option<SocketAddr>,
+
+    /// Periodically POST live Prometheus metrics to this push gateway URL
+    #[arg(long)]
+    prometheus_push: Option<String>,
+
+    /// What kind of requests to send
+    #[arg(long, default_value_t = RequestType::Matmul)]
+    request_type: RequestType,
+
+    /// Size (N) of the NxN matrix to multiply / number of iterations to compute
+    #[arg(long, default_value_t = 128)]
+    input_size: u64,
+
+    /// The IP of the HTTP storage server (used only for the composition experiment)
+    #[arg(long, default_value_t = String::from("localhost"))]
+    storage_ip: String,
+
+    /// The average percentage of hot requests to issue
+    #[arg(long, default_value_t = 1.0)]
+    hot_percent: f64,
+}
+
+struct HotGenerator {
+    hot_percent: f64,
+    request_counter: f64,
+}
+
+impl HotGenerator {
+    fn new(hot_percent: f64) -> Self {
+        assert!(hot_percent >= 0.0);
+        assert!(hot_percent <= 1.0);
+        Self {
+            hot_percent,
+            request_counter: rand::random(),
+        }
+    }
+
+    fn next(&mut self) -> bool {
+        self.request_counter += self.hot_percent;
+        if self.request_counter >= 1.0 {
+            self.request_counter -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Record {
+    start: SystemTime,
+    url: String,
+    timeout: bool,
+    error: bool,
+    status: Option<StatusCode>,
+    /// How long the request actually took once it was sent, i.e. `end - start`.
+    service_time: Duration,
+    /// How long the request took from when it *should* have been sent, i.e.
+    /// `end - scheduled_start`. This is the coordinated-omission-corrected latency and
+    /// includes time spent queued for a user slot.
+    corrected_latency: Duration,
+}
+
+impl Record {
+    fn start_time(&self) -> Duration {
+        self.start.duration_since(UNIX_EPOCH).unwrap()
+    }
+}
+
+struct BenchLog {
+    records: Vec<Record>,
+    timeouts: usize,
+    errors: usize,
+}
+
+impl BenchLog {
+    fn new(num_records: usize) -> Self {
+        Self {
+            records: Vec::with_capacity(num_records),
+            timeouts: 0,
+            errors: 0,
+        }
+    }
+
+    fn add_record(&mut self, record: Record) {
+        if record.timeout {
+            self.timeouts += 1;
+        }
+        if record.error {
+            self.errors += 1;
+        }
+        self.records.push(record);
+    }
+
+    fn total(&self) -> usize {
+        self.records.len()
+    }
+
+    fn errors(&self) -> usize {
+        self.timeouts + self.errors
+    }
+
+    fn latencies(&self, percentages: &[f64]) -> Vec<Duration> {
+        let mut latency: Vec<_> = self.records.iter().map(|t| t.corrected_latency).collect();
+        latency.sort();
+        percentages
+            .iter()
+            .map(|p| {
+                latency
+                    .get(((latency.len() as f64 * p - 1.0) / 100.0) as usize)
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+/// Build the sequence of target rates to sweep through: starting at `rate`, stepping by
+/// `rate_step` up to `rate_max`, then holding at `rate_max` for `max_iter` windows. With
+/// no `rate_step`/`rate_max` given, the sweep is just the single starting `rate`.
+fn sweep_rates(args: &Args) -> Vec<u64> {
+    let (Some(step), Some(max)) = (args.rate_step, args.rate_max) else {
+        return vec![args.rate];
+    };
+    assert!(step > 0, "--rate-step must be nonzero");
+    assert!(max >= args.rate, "--rate-max must be >= --rate");
+    let mut rates: Vec<_> = (args.rate..max).step_by(step as usize).collect();
+    rates.extend(std::iter::repeat_n(max, args.max_iter.max(1) as usize));
+    rates
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let args = Args::parse();
+    let rt = Builder::new_multi_thread().enable_all().build()?;
+    rt.block_on(tokio_main(args))
+}
+
+// #[tokio::main]
+async fn tokio_main(args: Args) -> Result<()> {
+    let rates = sweep_rates(&args);
+    let expected_checksum = args.request_type.checksum(args.input_size);
+    let num_samples = args.samples.max(1);
+    let args = Arc::new(args);
+
+    let client = Client::builder()
+        .timeout(Duration::from_millis(args.timeout))
+        .build()
+        .unwrap();
+    let mut hot_gen = HotGenerator::new(args.hot_percent);
+
+    let metrics = if args.prometheus_listen.is_some() || args.prometheus_push.is_some() {
+        Some(Metrics::new())
+    } else {
+        None
+    };
+    if let Some(metrics) = &metrics {
+        if let Some(addr) = args.prometheus_listen {
+            metrics.clone().serve(addr)?;
+        }
+        if let Some(gateway) = &args.prometheus_push {
+            metrics.clone().push(gateway.clone());
+        }
+    }
+
+    // Indexed as `samples[sample_idx][level_idx]`.
+    let mut samples = Vec::with_capacity(num_samples as usize);
+    for sample in 0..num_samples {
+        let mut levels = Vec::with_capacity(rates.len());
+        for &rate in &rates {
+            info!(
+                "Sample {}: starting measurement window at rate={}",
+                sample, rate
+            );
+            let (bench_log, hg) = if args.request_type == RequestType::Websocket {
+                run_level_ws(&args, hot_gen, rate, &metrics).await?
+            } else {
+                run_level(&args, &client, expected_checksum, hot_gen, rate, &metrics).await?
+            };
+            hot_gen = hg;
+            report_level(sample, rate, &bench_log, args.duration);
+            levels.push((rate, bench_log));
+        }
+        samples.push(levels);
+    }
+
+    if let Some(results_path) = &args.output_file {
+        let mut file = File::create(results_path)?;
+        writeln!(
+            file,
+            "sample,targetRate,instance,startTime,responseTime,correctedLatency,connectionTimeout,functionTimeout,statusCode",
+        )?;
+        for (sample, levels) in samples.iter().enumerate() {
+            for (rate, bench_log) in levels {
+                for record in &bench_log.records {
+                    writeln!(
+                        file,
+                        "{},{},{},{},{},{},{},{},{}",
+                        sample,
+                        rate,
+                        record.url,
+                        record.start_time().as_micros(),
+                        record.service_time.as_micros(),
+                        record.corrected_latency.as_micros(),
+                        record.timeout,
+                        record.error,
+                        record.status.map_or(0, |s| s.as_u16()),
+                    )?;
+                }
+            }
+        }
+    }
+
+    if let Some(summary_path) = &args.json_summary {
+        let summary = BenchmarkSummary::new(&rates, &samples, args.duration);
+        let mut file = File::create(summary_path)?;
+        writeln!(file, "{}", serde_json::to_string_pretty(&summary)?)?;
+    }
+
+    Ok(())
+}
+
+/// Builds the request arrival schedule for a measurement window and spawns the blocking
+/// timer task that paces it onto `step_tx`. Returns the expected number of requests (for
+/// `BenchLog` preallocation) and the timer task's handle.
+fn spawn_schedule(
+    args: &Args,
+    rate: u64,
+    step_tx: mpsc::Sender<SystemTime>,
+) -> (usize, tokio::task::JoinHandle<Result<()>>) {
+    // `--rate 0` means "as fast as possible" and has no schedule to build; only
+    // construct a generator when there actually is a target rate to pace against
+    // (an inter-arrival time of `1.0 / 0` would otherwise panic in the generator).
+    let starts: Option<Box<dyn ArrivalGen<Item = Duration> + Send>> = if rate == 0 {
+        None
+    } else {
+        Some(match args.arrival {
+            Arrival::Constant => Box::new(ConstGen::new(Duration::from_secs(args.duration), rate)),
+            Arrival::Poisson => Box::new(PoissonGen::new(Duration::from_secs(args.duration), rate)),
+        })
+    };
+    let expected_len = starts.as_deref().map_or(0, ArrivalGen::expected_len);
+
+    let duration = args.duration;
+    let task_timer = tokio::task::spawn_blocking(move || {
+        let base = Instant::now();
+        let base_wall = SystemTime::now();
+        match starts {
+            None => {
+                while base.elapsed().as_secs() < duration {
+                    step_tx.blocking_send(SystemTime::now()).unwrap();
+                }
+            }
+            Some(starts) => {
+                for start in starts {
+                    let next = base + start;
+                    if next.elapsed() > Duration::from_millis(REQ_ISSUE_SLACK_MS) {
+                        warn!("Could not keep up with needed rate, canceling experiment");
+                        let msg: Box<dyn std::error::Error + Send + Sync> =
+                            "Could not keep up".into();
+                        return Err(msg);
+                    }
+                    // higher precision than tokio::time::sleep
+                    std::thread::sleep(next - Instant::now());
+                    step_tx.blocking_send(base_wall + start).unwrap();
+                }
+            }
+        }
+        info!("Started all requests in {:?}", base.elapsed());
+        Ok(())
+    });
+
+    (expected_len, task_timer)
+}
+
+/// Run a single measurement window at a fixed offered `rate` for `args.duration` seconds,
+/// returning the `BenchLog` collected over that window.
+async fn run_level(
+    args: &Arc<Args>,
+    client: &Client,
+    expected_checksum: u64,
+    hot_gen: HotGenerator,
+    rate: u64,
+    metrics: &Option<Metrics>,
+) -> Result<(BenchLog, HotGenerator)> {
+    let (step_tx, mut step_rx) = mpsc::channel(100);
+    let (user_tx, mut user_rx) = mpsc::channel(args.num_users);
+    let (record_tx, mut record_rx) = mpsc::channel(100);
+
+    let (expected_len, task_timer) = spawn_schedule(args, rate, step_tx);
+    let mut bench_log = BenchLog::new(expected_len + 1);
+
+    for user_id in 0..args.num_users {
+        user_tx.send(user_id).await.unwrap();
+    }
+
+    let dispatch_args = Arc::clone(args);
+    let client = client.clone();
+    let mut hot_gen = hot_gen;
+    let dispatch = tokio::spawn(async move {
+        while let Some(scheduled_start) = step_rx.recv().await {
+            let is_hot = hot_gen.next();
+            let url = dispatch_args.request_type.url(&dispatch_args.ip, is_hot);
+            let body = dispatch_args
+                .request_type
+                .body(dispatch_args.input_size, &dispatch_args.storage_ip);
+            let request = client.post(&url).body(body);
+
+            let user_id = user_rx.recv().await.unwrap();
+            let user_tx = user_tx.clone();
+            let record_tx = record_tx.clone();
+            tokio::spawn(async move {
+                let start = SystemTime::now();
+                let result = request.send().await;
+                let end = SystemTime::now();
+
+                let mut record = Record {
+                    start,
+                    url,
+                    timeout: false,
+                    error: false,
+                    status: None,
+                    service_time: end.duration_since(start).unwrap(),
+                    corrected_latency: end.duration_since(scheduled_start).unwrap(),
+                };
+                match result.and_then(|r| r.error_for_status()) {
+                    Ok(r) => {
+                        record.status = Some(r.status());
+                        let body = r.bytes().await.unwrap();
+                        assert_eq!(body.len(), 8);
+                        let mut buf = [0u8; 8];
+                        buf.copy_from_slice(&body[..8]);
+                        let checksum = u64::from_be_bytes(buf);
+                        assert_eq!(checksum, expected_checksum);
+                    }
+                    Err(e) => {
+                        if e.is_timeout() {
+                            record.timeout = true;
+                            debug!("Request timed out");
+                        } else {
+                            record.error = true;
+                            record.status = e.status();
+                            warn!("Request error: {}", e);
+                        }
+                    }
+                }
+                // user_rx could be dropped first (when timer_tx is closed), so we don't check the result here.
+                let _ = user_tx.send(user_id).await;
+                record_tx.send(record).await.unwrap();
+            });
+        }
+        hot_gen
+    });
+
+    while let Some(record) = record_rx.recv().await {
+        if let Some(metrics) = metrics {
+            metrics.record(
+                &record.url,
+                record.error,
+                record.timeout,
+                record.corrected_latency.as_micros() as u64,
+            );
+        }
+        bench_log.add_record(record);
+    }
+
+    task_timer.await??;
+    let hot_gen = dispatch.await?;
+
+    Ok((bench_log, hot_gen))
+}
+
+/// One message to be sent over an already-open WebSocket connection.
+struct WsJob {
+    payload: Vec<u8>,
+    scheduled_start: SystemTime,
+}
+
+/// Owns a single persistent WebSocket connection and drives it for the whole measurement
+/// window: each job is a send-and-wait-for-echo round trip, after which the connection
+/// (not a one-shot request) is handed back to the dispatch loop for reuse.
+async fn ws_connection_actor(
+    mut ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    url: String,
+    mut job_rx: mpsc::Receiver<WsJob>,
+    user_id: usize,
+    user_tx: mpsc::Sender<usize>,
+    record_tx: mpsc::Sender<Record>,
+    timeout: Duration,
+) -> Result<()> {
+    // Every job on this run carries the same constant payload, so a timed-out request's
+    // stale response left buffered in the stream would otherwise be indistinguishable from
+    // the next job's response and get matched to the wrong request. Tag each outgoing frame
+    // with a per-connection sequence number and only accept an echo carrying the current one.
+    let mut seq: u64 = 0;
+    while let Some(job) = job_rx.recv().await {
+        let seq_id = seq.to_be_bytes();
+        seq = seq.wrapping_add(1);
+        let mut frame = Vec::with_capacity(seq_id.len() + job.payload.len());
+        frame.extend_from_slice(&seq_id);
+        frame.extend_from_slice(&job.payload);
+
+        let start = SystemTime::now();
+        let round_trip = tokio::time::timeout(timeout, async {
+            ws.send(Message::Binary(frame)).await?;
+            loop {
+                match ws.next().await {
+                    Some(Ok(Message::Binary(echo))) if echo.starts_with(&seq_id) => return Ok(()),
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e),
+                    None => return Err(tokio_tungstenite::tungstenite::Error::ConnectionClosed),
+                }
+            }
+        })
+        .await;
+        let end = SystemTime::now();
+
+        let mut record = Record {
+            start,
+            url: url.clone(),
+            timeout: false,
+            error: false,
+            status: None,
+            service_time: end.duration_since(start).unwrap(),
+            corrected_latency: end.duration_since(job.scheduled_start).unwrap(),
+        };
+        match round_trip {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                record.error = true;
+                warn!("Websocket request error: {}", e);
+            }
+            Err(_) => {
+                record.timeout = true;
+                debug!("Websocket request timed out");
+            }
+        }
+        // user_rx could be dropped first (when timer_tx is closed), so we don't check the result here.
+        let _ = user_tx.send(user_id).await;
+        record_tx.send(record).await.unwrap();
+    }
+    Ok(())
+}
+
+/// Run a single measurement window over `args.num_users` persistent WebSocket
+/// connections instead of one-shot HTTP requests: each connection is opened once and
+/// reused for many messages, with latency measured per message round trip.
+async fn run_level_ws(
+    args: &Arc<Args>,
+    hot_gen: HotGenerator,
+    rate: u64,
+    metrics: &Option<Metrics>,
+) -> Result<(BenchLog, HotGenerator)> {
+    let (step_tx, mut step_rx) = mpsc::channel(100);
+    let (user_tx, mut user_rx) = mpsc::channel(args.num_users);
+    let (record_tx, mut record_rx) = mpsc::channel(100);
+    let timeout = Duration::from_millis(args.timeout);
+
+    let mut hot_gen = hot_gen;
+    let mut connections = Vec::with_capacity(args.num_users);
+    let mut actor_handles = Vec::with_capacity(args.num_users);
+    for user_id in 0..args.num_users {
+        let is_hot = hot_gen.next();
+        let url = args.request_type.url(&args.ip, is_hot);
+        // Don't free the user slot for dispatch until the connection is actually up.
+        let (ws, _) = connect_async(&url).await?;
+        let (job_tx, job_rx) = mpsc::channel(1);
+        connections.push(job_tx);
+        actor_handles.push(tokio::spawn(ws_connection_actor(
+            ws,
+            url,
+            job_rx,
+            user_id,
+            user_tx.clone(),
+            record_tx.clone(),
+            timeout,
+        )));
+        user_tx.send(user_id).await.unwrap();
+    }
+    drop(record_tx);
+
+    let (expected_len, task_timer) = spawn_schedule(args, rate, step_tx);
+    let mut bench_log = BenchLog::new(expected_len + 1);
+
+    let input_size = args.input_size;
+    let storage_ip = args.storage_ip.clone();
+    let request_type = args.request_type;
+    let dispatch = tokio::spawn(async move {
+        while let Some(scheduled_start) = step_rx.recv().await {
+            let body = request_type.body(input_size, &storage_ip);
+            let user_id = user_rx.recv().await.unwrap();
+            // The connection's actor task may already have exited, so we don't check the result here.
+            let _ = connections[user_id]
+                .send(WsJob {
+                    payload: body,
+                    scheduled_start,
+                })
+                .await;
+        }
+    });
+
+    while let Some(record) = record_rx.recv().await {
+        if let Some(metrics) = metrics {
+            metrics.record(
+                &record.url,
+                record.error,
+                record.timeout,
+                record.corrected_latency.as_micros() as u64,
+            );
+        }
+        bench_log.add_record(record);
+    }
+
+    task_timer.await??;
+    dispatch.await?;
+    for handle in actor_handles {
+        handle.await??;
+    }
+
+    Ok((bench_log, hot_gen))
+}
+
+/// Total/error/percentile/goodput statistics for a single measurement window.
+#[derive(Serialize)]
+struct LevelStats {
+    rate: u64,
+    total: usize,
+    errors: usize,
+    error_rate: f64,
+    goodput: f64,
+    /// `(percentage, microseconds)` pairs, one per entry of [`PERCENTAGES`].
+    percentiles: Vec<(f64, u128)>,
+}
+
+impl LevelStats {
+    fn new(rate: u64, bench_log: &BenchLog, duration: u64) -> Self {
+        let total = bench_log.total();
+        let errors = bench_log.errors();
+        let latencies = bench_log.latencies(&PERCENTAGES);
+        Self {
+            rate,
+            total,
+            errors,
+            // A zero-total window (e.g. --duration 0) would otherwise divide 0.0/0.0 into
+            // NaN, which later blows up median's partial_cmp.
+            error_rate: if total == 0 {
+                0.0
+            } else {
+                errors as f64 / total as f64
+            },
+            goodput: if duration == 0 {
+                0.0
+            } else {
+                (total - errors) as f64 / duration as f64
+            },
+            percentiles: PERCENTAGES
+                .into_iter()
+                .zip(latencies.into_iter().map(|l| l.as_micros()))
+                .collect(),
+        }
+    }
+}
+
+fn report_level(sample: u64, rate: u64, bench_log: &BenchLog, duration: u64) {
+    let stats = LevelStats::new(rate, bench_log, duration);
+    println!("--- sample={} rate={} ---", sample, rate);
+    println!("Total: {}, Errors: {}", stats.total, stats.errors);
+    println!("Latency percentiles (in us):");
+    for (p, l) in &stats.percentiles {
+        println!("{:5}% -- {}\t", p, l);
+    }
+    println!(
+        "error%=\"{}\" goodput=\"{}\"",
+        stats.error_rate, stats.goodput,
+    );
+}
+
+fn mean(xs: &[f64]) -> f64 {
+    xs.iter().sum::<f64>() / xs.len() as f64
+}
+
+fn median(xs: &[f64]) -> f64 {
+    let mut xs = xs.to_vec();
+    xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = xs.len() / 2;
+    if xs.len().is_multiple_of(2) {
+        (xs[mid - 1] + xs[mid]) / 2.0
+    } else {
+        xs[mid]
+    }
+}
+
+/// Mean and median of a statistic across samples.
+#[derive(Serialize)]
+struct AggregatedStat {
+    mean: f64,
+    median: f64,
+}
+
+impl AggregatedStat {
+    fn new(values: &[f64]) -> Self {
+        Self {
+            mean: mean(values),
+            median: median(values),
+        }
+    }
+}
+
+/// Aggregated statistics for a single target rate across all samples.
+#[derive(Serialize)]
+struct LevelSummary {
+    rate: u64,
+    samples: Vec<LevelStats>,
+    error_rate: AggregatedStat,
+    goodput: AggregatedStat,
+    /// `(percentage, aggregated microseconds)` pairs, one per entry of [`PERCENTAGES`].
+    percentiles: Vec<(f64, AggregatedStat)>,
+}
+
+impl LevelSummary {
+    fn new(samples: Vec<LevelStats>) -> Self {
+        let rate = samples[0].rate;
+        let error_rate =
+            AggregatedStat::new(&samples.iter().map(|s| s.error_rate).collect::<Vec<_>>());
+        let goodput = AggregatedStat::new(&samples.iter().map(|s| s.goodput).collect::<Vec<_>>());
+        let percentiles = PERCENTAGES
+            .into_iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let at_p: Vec<_> = samples.iter().map(|s| s.percentiles[i].1 as f64).collect();
+                (p, AggregatedStat::new(&at_p))
+            })
+            .collect();
+        Self {
+            rate,
+            samples,
+            error_rate,
+            goodput,
+            percentiles,
+        }
+    }
+}
+
+/// Per-sample and mean/median-aggregated statistics for an entire rate sweep run with
+/// `--samples N`, so a single noisy run doesn't dominate the reported numbers.
+#[derive(Serialize)]
+struct BenchmarkSummary {
+    levels: Vec<LevelSummary>,
+}
+
+impl BenchmarkSummary {
+    fn new(rates: &[u64], samples: &[Vec<(u64, BenchLog)>], duration: u64) -> Self {
+        let levels = rates
+            .iter()
+            .enumerate()
+            .map(|(level_idx, &rate)| {
+                let per_sample = samples
+                    .iter()
+                    .map(|levels| LevelStats::new(rate, &levels[level_idx].1, duration))
+                    .collect();
+                LevelSummary::new(per_sample)
+            })
+            .collect();
+        Self { levels }
+    }
+}
@@ -0,0 +1,138 @@
+//! Live Prometheus-format metrics for the in-flight benchmark, so a long sweep can be
+//! watched in Grafana instead of waiting for the final stdout dump.
+
+use reqwest::Client;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+const QUANTILES: [f64; 6] = [0.5, 0.9, 0.95, 0.99, 0.999, 1.0];
+const PUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct LabelCounters {
+    requests_total: u64,
+    errors_total: u64,
+    timeouts_total: u64,
+    latencies_us: Vec<u64>,
+}
+
+/// Request-type and hot/cold label pair, e.g. `("matmul", "hot")`.
+type Labels = (String, String);
+
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Mutex<HashMap<Labels, LabelCounters>>>);
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the outcome of one request, reading its `request_type`/hot-cold labels out
+    /// of the request URL (`http://host:port/{hot,cold}/{request_type}`).
+    pub fn record(&self, url: &str, error: bool, timeout: bool, latency_us: u64) {
+        let mut segments = url.rsplit('/');
+        let request_type = segments.next().unwrap_or("unknown").to_string();
+        let hot_cold = segments.next().unwrap_or("unknown").to_string();
+
+        let mut state = self.0.lock().unwrap();
+        let counters = state.entry((request_type, hot_cold)).or_default();
+        counters.requests_total += 1;
+        if error {
+            counters.errors_total += 1;
+        }
+        if timeout {
+            counters.timeouts_total += 1;
+        }
+        counters.latencies_us.push(latency_us);
+    }
+
+    /// Render the current state in Prometheus text exposition format.
+    fn render(&self) -> String {
+        let state = self.0.lock().unwrap();
+        let mut out = String::new();
+        for ((request_type, hot_cold), counters) in state.iter() {
+            // `instance` is a reserved Prometheus label (the scrape target address) that
+            // gets overwritten on scrape unless `honor_labels: true` is set, so the
+            // hot/cold dimension is exposed as `state` instead.
+            let labels = format!("request_type=\"{}\",state=\"{}\"", request_type, hot_cold);
+            out += &format!(
+                "rload_requests_total{{{}}} {}\n",
+                labels, counters.requests_total
+            );
+            out += &format!(
+                "rload_errors_total{{{}}} {}\n",
+                labels, counters.errors_total
+            );
+            out += &format!(
+                "rload_timeouts_total{{{}}} {}\n",
+                labels, counters.timeouts_total
+            );
+
+            let mut latencies = counters.latencies_us.clone();
+            latencies.sort_unstable();
+            for q in QUANTILES {
+                let us = latencies
+                    .get(((latencies.len() as f64 * q - 1.0) / 1.0).max(0.0) as usize)
+                    .copied()
+                    .unwrap_or_default();
+                out += &format!("rload_latency_us{{{},quantile=\"{}\"}} {}\n", labels, q, us);
+            }
+        }
+        out
+    }
+
+    /// Serve `/metrics` on `addr` until the process exits.
+    pub fn serve(self, addr: std::net::SocketAddr) -> crate::Result<()> {
+        tokio::spawn(async move {
+            let listener = TcpListener::bind(addr)
+                .await
+                .expect("bind prometheus-listen addr");
+            info!("Serving Prometheus metrics on http://{}/metrics", addr);
+            loop {
+                let (mut socket, _) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        warn!("Failed to accept metrics connection: {}", e);
+                        continue;
+                    }
+                };
+                let metrics = self.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    // We don't care about the request line/path, every connection gets /metrics.
+                    let _ = socket.read(&mut buf).await;
+                    let body = metrics.render();
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body,
+                    );
+                    let _ = socket.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        Ok(())
+    }
+
+    /// Periodically POST the current metrics to a Prometheus push gateway until the
+    /// process exits.
+    pub fn push(self, gateway: String) {
+        tokio::spawn(async move {
+            let client = Client::new();
+            loop {
+                let body = self.render();
+                if let Err(e) = client.post(&gateway).body(body).send().await {
+                    warn!("Failed to push metrics to {}: {}", gateway, e);
+                }
+                tokio::time::sleep(PUSH_INTERVAL).await;
+            }
+        });
+    }
+}
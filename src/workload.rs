@@ -5,8 +5,14 @@ use std::io::Cursor;
 // Consider implementing this using dynamic dispatch
 impl RequestType {
     pub fn url(&self, ip: &str, is_hot: bool) -> String {
+        let scheme = if matches!(self, Self::Websocket) {
+            "ws"
+        } else {
+            "http"
+        };
         format!(
-            "http://{}:{}/{}/{}",
+            "{}://{}:{}/{}/{}",
+            scheme,
             ip,
             8080,
             if is_hot { "hot" } else { "cold" },
@@ -14,6 +20,7 @@ impl RequestType {
                 Self::Matmul => "matmul",
                 Self::Compute => "compute",
                 Self::Io => "io",
+                Self::Websocket => "websocket",
             }
         )
     }
@@ -39,6 +46,8 @@ impl RequestType {
                 let post_uri = format!("http://{}:{}/post", storage_ip, 8000);
                 format!("{}::{}", get_uri, post_uri).into_bytes()
             }
+            // Payload doesn't need to encode anything, the server just echoes it back.
+            Self::Websocket => vec![0u8; input_size as usize],
         }
     }
 
@@ -60,6 +69,8 @@ impl RequestType {
             }
             Self::Compute => input_size,
             Self::Io => 2000000,
+            // Unused: Websocket responses are matched by their sequence-number prefix, not a checksum.
+            Self::Websocket => 0,
         }
     }
 }